@@ -1,9 +1,12 @@
 use std::fmt;
 use std::rc::Rc;
 use std::sync::Arc;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use types::{Params, Value, Error};
-use futures::{Future, IntoFuture};
+use futures::{future, Future, IntoFuture};
 use BoxFuture;
+use pubsub::{SubscribeRpcMethod, UnsubscribeRpcMethod};
 
 /// Metadata trait
 pub trait Metadata: Clone + 'static {}
@@ -13,6 +16,50 @@ impl<T: Metadata> Metadata for Box<T> {}
 impl<T: 'static> Metadata for Rc<T> {}
 impl<T: 'static> Metadata for Arc<T> {}
 
+/// Request context a transport hands to a `MetaExtractor` when a connection opens.
+#[derive(Debug, Default, Clone)]
+pub struct RequestContext {
+	/// Peer address, when the transport exposes one.
+	pub peer_addr: Option<::std::net::SocketAddr>,
+	/// Connection headers (e.g. the WebSocket upgrade request headers).
+	pub headers: Vec<(String, String)>,
+}
+
+/// Builds a fresh per-connection metadata `T` from the request context.
+pub trait MetaExtractor<T: Metadata>: 'static {
+	/// Extracts metadata for a newly opened connection.
+	fn extract(&self, context: &RequestContext) -> T;
+}
+
+impl<T, F> MetaExtractor<T> for F where
+	T: Metadata,
+	F: Fn(&RequestContext) -> T + 'static,
+{
+	fn extract(&self, context: &RequestContext) -> T {
+		(*self)(context)
+	}
+}
+
+impl<T: Default + Metadata> MetaExtractor<T> for () {
+	fn extract(&self, _context: &RequestContext) -> T {
+		T::default()
+	}
+}
+
+/// Metadata carrying a connection lifecycle.
+///
+/// A transport can construct `T` once per connection (via a `MetaExtractor`)
+/// and call `on_session_start` on connect and `on_session_end` on close.
+/// Nothing in this crate calls the hooks; they run only where a transport
+/// invokes them. Both hooks default to no-ops, so existing `Metadata` types
+/// opt in only when they care.
+pub trait SessionMetadata: Metadata {
+	/// Called once, when the connection is established.
+	fn on_session_start(&self) {}
+	/// Called once, when the connection is closed.
+	fn on_session_end(&self) {}
+}
+
 /// Asynchronous Method
 pub trait RpcMethodSimple: 'static {
 	/// Output future
@@ -48,6 +95,10 @@ pub enum RemoteProcedure<T: Metadata> {
 	Notification(Arc<RpcNotification<T>>),
 	/// An alias to other method,
 	Alias(String),
+	/// A subscription request: opens a long-lived sink keyed by a `SubscriptionId`.
+	Subscription(String, Arc<SubscribeRpcMethod<T>>),
+	/// An unsubscription request: tears down the sink opened by `Subscription`.
+	Unsubscription(Arc<UnsubscribeRpcMethod<T>>),
 }
 
 impl<T: Metadata> fmt::Debug for RemoteProcedure<T> {
@@ -56,7 +107,9 @@ impl<T: Metadata> fmt::Debug for RemoteProcedure<T> {
 		match *self {
 			Method(..) => write!(fmt, "<method>"),
 			Notification(..) => write!(fmt, "<notification>"),
-			Alias(ref alias) => write!(fmt, "alias => {:?}", alias)
+			Alias(ref alias) => write!(fmt, "alias => {:?}", alias),
+			Subscription(ref subscribe, ..) => write!(fmt, "<subscription {:?}>", subscribe),
+			Unsubscription(..) => write!(fmt, "<unsubscription>"),
 		}
 	}
 }
@@ -99,3 +152,34 @@ impl<F: 'static, T> RpcNotification<T> for F where
 		self(params, meta)
 	}
 }
+
+/// Wraps a strongly typed closure into an `RpcMethod<T>`.
+///
+/// The raw `Params` are deserialized into `A` (deserialization failures become
+/// `Error::invalid_params`), the closure runs, and the typed `R` it yields is
+/// serialized back into `Value`. Lets handlers carry natural Rust signatures
+/// instead of hand-rolling `params.parse()` and `to_value(result)`, while the
+/// plain `Fn(Params, T)` impl above keeps working unchanged.
+pub fn wrap_method<T, A, R, F, I>(f: F) -> impl Fn(Params, T) -> BoxFuture<Value> where
+	T: Metadata,
+	A: DeserializeOwned,
+	R: Serialize + 'static,
+	F: Fn(A, T) -> I + 'static,
+	I: IntoFuture<Item = R, Error = Error>,
+	I::Future: 'static,
+{
+	move |params: Params, meta: T| -> BoxFuture<Value> {
+		match params.parse::<A>() {
+			Ok(param) => Box::new(
+				f(param, meta).into_future()
+					.and_then(|result| ::serde_json::to_value(&result)
+						.map_err(|e| {
+							let mut error = Error::internal_error();
+							error.message = format!("Could not serialize result: {}", e);
+							error
+						}))
+			),
+			Err(e) => Box::new(future::err(e)),
+		}
+	}
+}
@@ -0,0 +1,155 @@
+//! A reusable, transport-agnostic JSON-RPC client.
+//!
+//! Every consumer of the crate used to reinvent the same loop: serialize a
+//! `MethodCall`, stash a oneshot `Complete` in a `HashMap<Id, _>` behind a
+//! `Mutex`, and resolve it when the matching `Success`/`Failure` arrives.
+//! [`RpcClient`] owns that pending-request map and the id allocation once, and
+//! additionally routes unmatched `method`-style frames to the subscription
+//! callbacks registered for them, so it can also consume the pub/sub
+//! notifications the server pushes (see [`pubsub`](super::pubsub)).
+
+use std::sync::{atomic, Arc, Mutex};
+use std::collections::HashMap;
+use types::{Id, Params, Value, Version, Error, MethodCall, Notification, Output, Call};
+use futures::{Future, Stream};
+use futures::sync::oneshot;
+use pubsub::SubscriptionId;
+use BoxFuture;
+
+/// Serialized messages in, serialized messages out: the minimal surface a
+/// transport must expose.
+///
+/// `ws`, raw TCP, or an in-process channel can all implement this; the client
+/// never assumes framing beyond "one serialized JSON message per item".
+pub trait Transport: 'static {
+	/// Sends a single serialized message.
+	fn send(&self, raw: String) -> BoxFuture<()>;
+	/// Returns the stream of incoming serialized messages.
+	fn messages(&self) -> Box<Stream<Item = String, Error = Error>>;
+}
+
+/// Callback invoked for every notification frame of a given subscription.
+pub type SubscriptionCallback = Box<Fn(Value)>;
+
+type Pending = Arc<Mutex<HashMap<Id, oneshot::Sender<Result<Value, Error>>>>>;
+type Subscriptions = Arc<Mutex<HashMap<SubscriptionId, SubscriptionCallback>>>;
+
+/// A transport-agnostic asynchronous JSON-RPC client.
+pub struct RpcClient<T: Transport> {
+	transport: T,
+	next_id: atomic::AtomicUsize,
+	pending: Pending,
+	subscriptions: Subscriptions,
+}
+
+impl<T: Transport> RpcClient<T> {
+	/// Creates a new client over the given transport.
+	pub fn new(transport: T) -> Self {
+		RpcClient {
+			transport: transport,
+			next_id: atomic::AtomicUsize::new(1),
+			pending: Arc::new(Mutex::new(HashMap::new())),
+			subscriptions: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	fn next_id(&self) -> Id {
+		Id::Num(self.next_id.fetch_add(1, atomic::Ordering::SeqCst) as u64)
+	}
+
+	/// Calls `name` with `params`, resolving with the method result.
+	///
+	/// Allocates a fresh `Id`, registers a pending slot for it and only then
+	/// writes the call to the transport, so the response can never race ahead
+	/// of the slot it completes.
+	pub fn call_method(&self, name: &str, params: Params) -> BoxFuture<Value> {
+		let id = self.next_id();
+		let (tx, rx) = oneshot::channel();
+		self.pending.lock().unwrap().insert(id.clone(), tx);
+
+		let call = MethodCall {
+			jsonrpc: Some(Version::V2),
+			method: name.into(),
+			params: params,
+			id: id.clone(),
+		};
+		let raw = ::serde_json::to_string(&call)
+			.expect("MethodCall is always serializable; qed");
+
+		let pending = self.pending.clone();
+		let send = self.transport.send(raw);
+		Box::new(
+			send
+				.and_then(move |_| rx.map_err(|_| Error::internal_error()))
+				.then(move |res| {
+					// On any failure (send or cancelled channel) reclaim the slot.
+					if res.is_err() {
+						pending.lock().unwrap().remove(&id);
+					}
+					res
+				})
+				.and_then(|res| res)
+		)
+	}
+
+	/// Sends a fire-and-forget notification; no response is awaited.
+	pub fn notify(&self, name: &str, params: Params) -> BoxFuture<()> {
+		let notification = Notification {
+			jsonrpc: Some(Version::V2),
+			method: name.into(),
+			params: params,
+		};
+		let raw = ::serde_json::to_string(&notification)
+			.expect("Notification is always serializable; qed");
+		self.transport.send(raw)
+	}
+
+	/// Registers a callback for notifications carrying the given subscription id.
+	pub fn on_subscription(&self, id: SubscriptionId, callback: SubscriptionCallback) {
+		self.subscriptions.lock().unwrap().insert(id, callback);
+	}
+
+	/// Forgets a subscription callback.
+	pub fn remove_subscription(&self, id: &SubscriptionId) -> bool {
+		self.subscriptions.lock().unwrap().remove(id).is_some()
+	}
+
+	/// Drives the incoming message stream, completing pending calls and
+	/// dispatching subscription notifications, until the transport ends.
+	pub fn run(&self) -> BoxFuture<()> {
+		let pending = self.pending.clone();
+		let subscriptions = self.subscriptions.clone();
+		Box::new(self.transport.messages().for_each(move |raw| {
+			dispatch(&raw, &pending, &subscriptions);
+			Ok(())
+		}))
+	}
+}
+
+/// Routes a single incoming frame to the pending slot or subscription it belongs to.
+fn dispatch(raw: &str, pending: &Pending, subscriptions: &Subscriptions) {
+	// A response to one of our calls?
+	if let Ok(output) = ::serde_json::from_str::<Output>(raw) {
+		let (id, result) = match output {
+			Output::Success(s) => (s.id, Ok(s.result)),
+			Output::Failure(f) => (f.id, Err(f.error)),
+		};
+		if let Some(tx) = pending.lock().unwrap().remove(&id) {
+			let _ = tx.send(result);
+		}
+		return;
+	}
+
+	// Otherwise a server push: `{"method": ..., "params": {"subscription": id, "result": ...}}`.
+	if let Ok(Call::Notification(notification)) = ::serde_json::from_str::<Call>(raw) {
+		if let Params::Map(ref map) = notification.params {
+			let id = map.get("subscription").and_then(SubscriptionId::parse_value);
+			let result = map.get("result").cloned().unwrap_or(Value::Null);
+			if let Some(id) = id {
+				if let Some(callback) = subscriptions.lock().unwrap().get(&id) {
+					callback(result);
+				}
+			}
+		}
+	}
+}
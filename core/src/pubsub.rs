@@ -0,0 +1,269 @@
+//! Publish-Subscribe extensions.
+//!
+//! The base request/response cycle only lets a handler answer the call it was
+//! given. A subscription instead keeps a long-lived sink open so the server
+//! can *push* notifications correlated by a `SubscriptionId` long after the
+//! original method returned. The handler of a [`SubscribeRpcMethod`] picks
+//! that id (for instance from a [`SubscriptionIds`] allocator) and passes it to
+//! the `Subscriber`'s `assign_id`, which returns it to the caller as the
+//! initial method result and yields the `Sink` the handler pushes through. The
+//! `Subscriber` does not allocate ids itself.
+
+use std::sync::{atomic, Mutex, Arc};
+use std::collections::HashMap;
+use types::{Params, Value, Error};
+use futures::{Future, Sink as FuturesSink, IntoFuture};
+use futures::sync::{mpsc, oneshot};
+use BoxFuture;
+
+use super::Metadata;
+
+/// Unique subscription id.
+///
+/// Allocated when a subscription is accepted and echoed back in every pushed
+/// notification so the client can correlate frames.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SubscriptionId {
+	/// A numeric id.
+	Number(u64),
+	/// A string id.
+	String(String),
+}
+
+impl SubscriptionId {
+	/// Parses a `SubscriptionId` out of the `Value` carried on the wire.
+	pub fn parse_value(val: &Value) -> Option<SubscriptionId> {
+		match *val {
+			Value::String(ref val) => Some(SubscriptionId::String(val.clone())),
+			Value::Number(ref val) => val.as_u64().map(SubscriptionId::Number),
+			_ => None,
+		}
+	}
+}
+
+impl From<String> for SubscriptionId {
+	fn from(other: String) -> Self {
+		SubscriptionId::String(other)
+	}
+}
+
+impl From<u64> for SubscriptionId {
+	fn from(other: u64) -> Self {
+		SubscriptionId::Number(other)
+	}
+}
+
+impl From<SubscriptionId> for Value {
+	fn from(sub: SubscriptionId) -> Self {
+		match sub {
+			SubscriptionId::Number(val) => Value::Number(val.into()),
+			SubscriptionId::String(val) => Value::String(val),
+		}
+	}
+}
+
+/// Metadata extension exposing the per-connection [`Session`].
+///
+/// `T` surfaces the session so code holding only the metadata can register
+/// subscription teardowns with it, without knowing the concrete transport.
+/// The session does not build a `Subscriber`: `Subscriber::new` takes the
+/// notification name and a transport sender, and no session.
+pub trait PubSubMetadata: Metadata {
+	/// Returns the session associated with the current connection, if any.
+	fn session(&self) -> Option<Arc<Session>>;
+}
+
+impl<T: PubSubMetadata> PubSubMetadata for Option<T> {
+	fn session(&self) -> Option<Arc<Session>> {
+		self.as_ref().and_then(PubSubMetadata::session)
+	}
+}
+
+type RemoveSubscription = Box<Fn(&SubscriptionId)>;
+
+/// A handle representing an opened connection session.
+///
+/// Dropping the `Session` — which happens deterministically when the transport
+/// closes — runs every registered teardown, so the subscription sinks it owned
+/// are reclaimed and handlers observe cancellation.
+pub struct Session {
+	active_subscriptions: Mutex<HashMap<(SubscriptionId, String), RemoveSubscription>>,
+	on_drop: Mutex<Vec<Box<Fn()>>>,
+}
+
+impl Session {
+	/// Creates a new empty session.
+	pub fn new() -> Arc<Self> {
+		Arc::new(Session {
+			active_subscriptions: Mutex::new(HashMap::new()),
+			on_drop: Mutex::new(Vec::new()),
+		})
+	}
+
+	/// Registers a teardown callback for a subscription opened in this session.
+	pub fn add_subscription(&self, name: &str, id: &SubscriptionId, remove: RemoveSubscription) {
+		let previous = self.active_subscriptions.lock().unwrap()
+			.insert((id.clone(), name.into()), remove);
+		if let Some(remove) = previous {
+			warn!("Subscription id collision. Tearing down previous subscription.");
+			remove(id);
+		}
+	}
+
+	/// Forgets a subscription without invoking its teardown.
+	pub fn remove_subscription(&self, name: &str, id: &SubscriptionId) -> bool {
+		self.active_subscriptions.lock().unwrap()
+			.remove(&(id.clone(), name.into()))
+			.is_some()
+	}
+
+	/// Registers a callback fired once, when the session is dropped.
+	pub fn on_drop<F: Fn() + 'static>(&self, on_drop: F) {
+		self.on_drop.lock().unwrap().push(Box::new(on_drop));
+	}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		let mut active = self.active_subscriptions.lock().unwrap();
+		for ((id, _), remove) in active.drain() {
+			remove(&id);
+		}
+		for on_drop in self.on_drop.lock().unwrap().drain(..) {
+			on_drop();
+		}
+	}
+}
+
+/// A subscription sink.
+///
+/// Cloneable handle the handler keeps to push notification frames. Every item
+/// sent is wrapped as `{"method": "<name>", "params": {"subscription": id,
+/// "result": ...}}` before it hits the transport. Dropping the last clone lets
+/// the transport observe that the handler is done.
+#[derive(Clone)]
+pub struct Sink {
+	notification: String,
+	transport: mpsc::Sender<String>,
+	id: SubscriptionId,
+}
+
+impl Sink {
+	fn frame(&self, result: Value) -> String {
+		let mut params = ::serde_json::Map::new();
+		params.insert("subscription".into(), self.id.clone().into());
+		params.insert("result".into(), result);
+		let mut frame = ::serde_json::Map::new();
+		frame.insert("jsonrpc".into(), Value::String("2.0".into()));
+		frame.insert("method".into(), Value::String(self.notification.clone()));
+		frame.insert("params".into(), Value::Object(params));
+		::serde_json::to_string(&Value::Object(frame))
+			.expect("Notification frame is always serializable; qed")
+	}
+
+	/// Pushes a single `result` value to the subscriber.
+	pub fn notify(&self, result: Value) -> BoxFuture<()> {
+		let frame = self.frame(result);
+		Box::new(self.transport.clone().send(frame).map(|_| ()).map_err(|_| Error::internal_error()))
+	}
+}
+
+/// Subscriber handle.
+///
+/// Handed to a [`SubscribeRpcMethod`] handler. Call [`assign_id`] exactly once
+/// with the allocated id to return it synchronously to the caller and unlock
+/// the [`Sink`]; call [`reject`] to decline the subscription with an error.
+///
+/// [`assign_id`]: #method.assign_id
+/// [`reject`]: #method.reject
+pub struct Subscriber {
+	notification: String,
+	transport: mpsc::Sender<String>,
+	sender: oneshot::Sender<Result<SubscriptionId, Error>>,
+}
+
+impl Subscriber {
+	/// Creates a new `Subscriber` wired to the given transport sink.
+	///
+	/// The returned receiver resolves with the allocated id (or the rejection
+	/// error) and feeds the *initial* method response.
+	pub fn new(notification: String, transport: mpsc::Sender<String>)
+		-> (Self, oneshot::Receiver<Result<SubscriptionId, Error>>)
+	{
+		let (sender, receiver) = oneshot::channel();
+		let subscriber = Subscriber {
+			notification: notification,
+			transport: transport,
+			sender: sender,
+		};
+		(subscriber, receiver)
+	}
+
+	/// Consumes the `Subscriber`, assigning the subscription id.
+	///
+	/// The id is sent back as the method result *before* any notification can
+	/// fire. Returns the [`Sink`] on success, or `Err` if the caller already
+	/// hung up.
+	pub fn assign_id(self, id: SubscriptionId) -> Result<Sink, ()> {
+		let Subscriber { notification, transport, sender } = self;
+		sender.send(Ok(id.clone())).map_err(|_| ())?;
+		Ok(Sink {
+			notification: notification,
+			transport: transport,
+			id: id,
+		})
+	}
+
+	/// Rejects the subscription, returning `error` to the caller.
+	pub fn reject(self, error: Error) -> Result<(), ()> {
+		self.sender.send(Err(error)).map_err(|_| ())
+	}
+}
+
+/// Subscribe handler.
+///
+/// Bound on [`Metadata`] rather than [`PubSubMetadata`]: the handler only
+/// consumes the `Subscriber` it is handed, and building that `Subscriber`
+/// (`Subscriber::new`) needs no session.
+pub trait SubscribeRpcMethod<T: Metadata>: 'static {
+	/// Called when a client requests a new subscription.
+	fn call(&self, params: Params, meta: T, subscriber: Subscriber);
+}
+
+impl<F: 'static, T> SubscribeRpcMethod<T> for F where
+	F: Fn(Params, T, Subscriber),
+	T: Metadata,
+{
+	fn call(&self, params: Params, meta: T, subscriber: Subscriber) {
+		self(params, meta, subscriber)
+	}
+}
+
+/// Unsubscribe handler.
+pub trait UnsubscribeRpcMethod<T>: 'static {
+	/// Called when a client requests to cancel an existing subscription.
+	fn call(&self, id: SubscriptionId, meta: Option<T>) -> BoxFuture<Value>;
+}
+
+impl<F: 'static, X: 'static, T, I> UnsubscribeRpcMethod<T> for F where
+	F: Fn(SubscriptionId, Option<T>) -> I,
+	I: IntoFuture<Item = Value, Error = Error, Future = X>,
+	X: Future<Item = Value, Error = Error>,
+{
+	fn call(&self, id: SubscriptionId, meta: Option<T>) -> BoxFuture<Value> {
+		Box::new(self(id, meta).into_future())
+	}
+}
+
+/// A simple monotonic allocator of numeric subscription ids.
+#[derive(Default, Debug)]
+pub struct SubscriptionIds {
+	next: atomic::AtomicUsize,
+}
+
+impl SubscriptionIds {
+	/// Returns the next free subscription id.
+	pub fn next(&self) -> SubscriptionId {
+		SubscriptionId::Number(self.next.fetch_add(1, atomic::Ordering::SeqCst) as u64)
+	}
+}